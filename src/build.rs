@@ -1,13 +1,19 @@
-use crate::lexer::CTokenizer;
-use failure::Error;
-use ignore::Walk;
+use crate::lexer::{CodeTokenizer, Language, ALL_LANGUAGES};
+use failure::{format_err, Error};
+use ignore::WalkBuilder;
 use log::warn;
-use std::{fs, path::PathBuf};
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::PathBuf,
+    time::UNIX_EPOCH,
+};
 use structopt::StructOpt;
 use tantivy::{
-    doc,
-    schema::{self, Schema},
-    Index,
+    collector::TopDocs,
+    query::AllQuery,
+    schema::{self, Document, Field, Schema, Value},
+    Index, Term,
 };
 
 /// The build command creates a new tantivy index, from a code repository.
@@ -16,38 +22,166 @@ pub struct BuildOpts {
     /// The directory to build the index in.
     #[structopt(long = "index_dir", parse(from_os_str), default_value = ".looker")]
     index_dir: PathBuf,
+    /// The directories to search for code in. Multiple roots are merged into a single index.
+    #[structopt(parse(from_os_str), default_value = ".")]
+    search_dirs: Vec<PathBuf>,
+    /// Do not index files found shallower than this many directories below a search root.
+    #[structopt(long = "min-depth")]
+    min_depth: Option<usize>,
+    /// Do not descend deeper than this many directories below a search root.
+    #[structopt(long = "max-depth")]
+    max_depth: Option<usize>,
+    /// Follow symbolic links when walking the search directories.
+    #[structopt(long = "follow-links")]
+    follow_links: bool,
+}
+
+/// The update command incrementally re-indexes a code repository, only touching files that have
+/// changed since the index was last built.
+#[derive(Debug, StructOpt)]
+pub struct UpdateOpts {
+    /// The directory the index is located in.
+    #[structopt(long = "index_dir", parse(from_os_str), default_value = ".looker")]
+    index_dir: PathBuf,
     /// The directory to search for code in.
     #[structopt(parse(from_os_str), default_value = ".")]
     search_dir: PathBuf,
 }
 
-/// Create an index for later searching.
-pub fn build_index(opts: BuildOpts) -> Result<(), Error> {
-    // create the schema
+/// The fields of the schema shared by `build_index` and `update_index`. Every supported language
+/// gets its own content field, since a tantivy field can only be indexed with a single tokenizer.
+struct Fields {
+    file_name: Field,
+    language: Field,
+    mtime: Field,
+    /// Per-language content field, keyed by the language it is tokenized with.
+    content: HashMap<Language, Field>,
+}
+
+/// The name of the content field used to store and index a given language's file contents.
+pub(crate) fn content_field_name(language: Language) -> String {
+    format!("content_{}", language.name())
+}
+
+/// Build the tantivy schema used by both `build_index` and `update_index`, along with handles to
+/// every field in it.
+fn build_schema() -> (Schema, Fields) {
     let mut schema_builder = Schema::builder();
 
-    let file_name = schema_builder.add_text_field("file_name", schema::STORED);
-    let file_contents = {
-        let indexing_options = schema::TextFieldIndexing::default()
-            .set_tokenizer("c")
-            .set_index_option(schema::IndexRecordOption::WithFreqsAndPositions);
-        let field_options = schema::TextOptions::default()
-            .set_indexing_options(indexing_options)
-            .set_stored();
-        schema_builder.add_text_field("file_contents", field_options)
-    };
+    // indexed with the raw tokenizer (the whole path is a single term) as well as stored, since
+    // `update_index` deletes stale documents by an exact `file_name` term match.
+    let file_name = schema_builder.add_text_field("file_name", schema::STRING | schema::STORED);
+    let language = schema_builder.add_text_field("language", schema::STORED);
+    // the last time the indexed file was modified, in seconds since the unix epoch - used by
+    // `update_index` to work out which files need to be re-indexed.
+    let mtime = schema_builder.add_u64_field("mtime", schema::STORED);
+
+    let content = ALL_LANGUAGES
+        .iter()
+        .map(|&language| {
+            let indexing_options = schema::TextFieldIndexing::default()
+                .set_tokenizer(language.name())
+                .set_index_option(schema::IndexRecordOption::WithFreqsAndPositions);
+            let field_options = schema::TextOptions::default()
+                .set_indexing_options(indexing_options)
+                .set_stored();
+            let field = schema_builder.add_text_field(&content_field_name(language), field_options);
+            (language, field)
+        })
+        .collect();
+
     let schema = schema_builder.build();
 
-    // create the index
-    fs::create_dir_all(&opts.index_dir)?;
-    let index = Index::create_in_dir(opts.index_dir, schema)?;
+    (
+        schema,
+        Fields {
+            file_name,
+            language,
+            mtime,
+            content,
+        },
+    )
+}
 
-    // register the C tokenizer
-    index.tokenizers().register("c", CTokenizer);
+/// Look up the fields of an already-existing index's schema.
+fn lookup_fields(schema: &Schema) -> Result<Fields, Error> {
+    let get_field = |name: &str| {
+        schema
+            .get_field(name)
+            .ok_or_else(|| format_err!("Cannot find field '{}' in index", name))
+    };
 
-    // write to the index
-    let mut writer = index.writer(1_000_000_000)?;
-    Walk::new(opts.search_dir)
+    let content = ALL_LANGUAGES
+        .iter()
+        .map(|&language| Ok((language, get_field(&content_field_name(language))?)))
+        .collect::<Result<_, Error>>()?;
+
+    Ok(Fields {
+        file_name: get_field("file_name")?,
+        language: get_field("language")?,
+        mtime: get_field("mtime")?,
+        content,
+    })
+}
+
+/// Register every supported language's tokenizer with the index.
+pub(crate) fn register_tokenizers(index: &Index) {
+    for &language in ALL_LANGUAGES {
+        index
+            .tokenizers()
+            .register(language.name(), CodeTokenizer(language));
+    }
+}
+
+/// Read a file's contents and the time it was last modified, in seconds since the unix epoch.
+fn read_file(path: &PathBuf) -> Option<(String, u64)> {
+    let modified = match fs::metadata(path).and_then(|meta| meta.modified()) {
+        Ok(modified) => modified,
+        Err(error) => {
+            warn!("Reading metadata for {:?}: {:?}", path, error);
+            return None;
+        }
+    };
+    let modified = modified
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0);
+
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(error) => {
+            warn!("Reading file: {:?}", error);
+            return None;
+        }
+    };
+
+    Some((contents, modified))
+}
+
+/// Walk `search_dir`, yielding the path and language of every recognised source file found
+/// within it.
+fn walk_source_files(search_dir: PathBuf) -> impl Iterator<Item = (PathBuf, Language)> {
+    walk_source_roots(&[search_dir], None, None, false)
+}
+
+/// Walk every directory in `search_dirs`, merging the streams, and yielding the path and
+/// language of every recognised source file found within any of them. `min_depth` / `max_depth`
+/// / `follow_links` are applied to each root individually.
+fn walk_source_roots(
+    search_dirs: &[PathBuf],
+    min_depth: Option<usize>,
+    max_depth: Option<usize>,
+    follow_links: bool,
+) -> impl Iterator<Item = (PathBuf, Language)> {
+    let min_depth = min_depth.unwrap_or(0);
+
+    search_dirs
+        .iter()
+        .flat_map(move |search_dir| {
+            let mut builder = WalkBuilder::new(search_dir);
+            builder.max_depth(max_depth).follow_links(follow_links);
+            builder.build()
+        })
         // remove errors (logging them)
         .filter_map(|file| match file {
             Ok(file) => Some(file),
@@ -56,6 +190,9 @@ pub fn build_index(opts: BuildOpts) -> Result<(), Error> {
                 None
             }
         })
+        // `ignore::WalkBuilder` has no `min_depth` of its own (unlike `walkdir`), so enforce it
+        // by filtering on each entry's depth instead.
+        .filter(move |file| file.depth() >= min_depth)
         // remove directories
         .filter(|file| {
             if let Some(file_type) = file.file_type() {
@@ -64,32 +201,202 @@ pub fn build_index(opts: BuildOpts) -> Result<(), Error> {
                 false
             }
         })
-        // make sure we only get c and h files in our index
         .map(|file| file.into_path())
-        .filter(|path| {
-            if let Some(extension) = path.extension() {
-                extension == "c" || extension == "h"
-            } else {
-                false
-            }
+        // only keep files whose extension maps to a language we know how to tokenize
+        .filter_map(|path| {
+            let language = path
+                .extension()
+                .and_then(|extension| extension.to_str())
+                .and_then(Language::from_extension)?;
+            Some((path, language))
         })
-        .for_each(|path| {
-            let contents = match fs::read_to_string(&path) {
-                Ok(contents) => contents,
-                Err(error) => {
-                    warn!("Reading file: {:?}", error);
-                    return;
-                }
+}
+
+/// Build a document for a single source file, tagging it with its language and writing its
+/// contents into that language's dedicated content field.
+fn build_document(fields: &Fields, name: String, language: Language, contents: String, modified: u64) -> Document {
+    let mut document = Document::new();
+    document.add_text(fields.file_name, &name);
+    document.add_text(fields.language, language.name());
+    document.add_u64(fields.mtime, modified);
+    document.add_text(fields.content[&language], &contents);
+    document
+}
+
+/// Create an index for later searching.
+pub fn build_index(opts: BuildOpts) -> Result<(), Error> {
+    let (schema, fields) = build_schema();
+
+    // create the index
+    fs::create_dir_all(&opts.index_dir)?;
+    let index = Index::create_in_dir(opts.index_dir, schema)?;
+    register_tokenizers(&index);
+
+    // write to the index
+    let mut writer = index.writer(1_000_000_000)?;
+    walk_source_roots(
+        &opts.search_dirs,
+        opts.min_depth,
+        opts.max_depth,
+        opts.follow_links,
+    )
+    .for_each(|(path, language)| {
+        let (contents, modified) = match read_file(&path) {
+            Some(result) => result,
+            None => return,
+        };
+        let name = path.into_os_string().to_string_lossy().to_string();
+
+        writer.add_document(build_document(&fields, name, language, contents, modified));
+    });
+
+    writer.commit()?;
+
+    Ok(())
+}
+
+/// Incrementally re-index a code repository, only re-reading files whose modification time (to
+/// the second) differs from the one stored when they were last built or updated, and removing
+/// files that no longer exist on disk.
+pub fn update_index(opts: UpdateOpts) -> Result<(), Error> {
+    let UpdateOpts {
+        index_dir,
+        search_dir,
+    } = opts;
+
+    let index = Index::open_in_dir(&index_dir)?;
+    register_tokenizers(&index);
+
+    let fields = lookup_fields(&index.schema())?;
+
+    // work out which files are already indexed, and when they were last indexed
+    let searcher = index.reader()?.searcher();
+    let mut indexed_mtimes: HashMap<String, u64> = HashMap::new();
+
+    // `TopDocs::with_limit` panics on a limit of 0, which `num_docs()` is for a freshly-built,
+    // still-empty index - there's nothing to read back in that case anyway.
+    if searcher.num_docs() > 0 {
+        let already_indexed =
+            searcher.search(&AllQuery, &TopDocs::with_limit(searcher.num_docs() as usize))?;
+
+        for (_score, address) in already_indexed {
+            let doc = searcher.doc(address)?;
+            let name = match doc.get_first(fields.file_name) {
+                Some(Value::Str(text)) => text.clone(),
+                _ => continue,
+            };
+            let modified = match doc.get_first(fields.mtime) {
+                Some(Value::U64(value)) => *value,
+                _ => continue,
             };
-            let name = path.into_os_string().to_string_lossy().to_string();
+            indexed_mtimes.insert(name, modified);
+        }
+    }
 
-            writer.add_document(doc! {
-                file_name => name,
-                file_contents => contents,
-            });
-        });
+    let mut writer = index.writer(1_000_000_000)?;
+    let mut seen = HashSet::new();
+
+    walk_source_files(search_dir).for_each(|(path, language)| {
+        let name = path.to_string_lossy().to_string();
+        seen.insert(name.clone());
+
+        if indexed_mtimes.get(&name).is_none() {
+            // new file, fall through to indexing below
+        } else {
+            let on_disk_mtime = fs::metadata(&path)
+                .and_then(|meta| meta.modified())
+                .ok()
+                .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                .map(|duration| duration.as_secs());
+
+            if on_disk_mtime == indexed_mtimes.get(&name).copied() {
+                // unchanged since it was last indexed
+                return;
+            }
+        }
+
+        let (contents, modified) = match read_file(&path) {
+            Some(result) => result,
+            None => return,
+        };
+
+        writer.delete_term(Term::from_field_text(fields.file_name, &name));
+        writer.add_document(build_document(&fields, name, language, contents, modified));
+    });
+
+    // purge anything that used to be indexed but is no longer on disk
+    for name in indexed_mtimes.keys() {
+        if !seen.contains(name) {
+            writer.delete_term(Term::from_field_text(fields.file_name, name));
+        }
+    }
 
     writer.commit()?;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tantivy::{collector::Count, query::TermQuery};
+
+    /// A scratch directory under the system temp dir, unique to this test process, removed on
+    /// drop.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("looker-test-{}-{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&path);
+            fs::create_dir_all(&path).unwrap();
+            TempDir(path)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn count_docs_for_file_name(index_dir: &PathBuf, file_name: &str) -> usize {
+        let index = Index::open_in_dir(index_dir).unwrap();
+        let fields = lookup_fields(&index.schema()).unwrap();
+        let searcher = index.reader().unwrap().searcher();
+        let term = Term::from_field_text(fields.file_name, file_name);
+        let query = TermQuery::new(term, schema::IndexRecordOption::Basic);
+        searcher.search(&query, &Count).unwrap()
+    }
+
+    #[test]
+    fn update_purges_the_stale_copy_of_a_changed_file() {
+        let search_dir = TempDir::new("update-search");
+        let index_dir = TempDir::new("update-index");
+
+        let source_file = search_dir.0.join("example.c");
+        fs::write(&source_file, "int main() { return 0; }").unwrap();
+
+        build_index(BuildOpts {
+            index_dir: index_dir.0.clone(),
+            search_dirs: vec![search_dir.0.clone()],
+            min_depth: None,
+            max_depth: None,
+            follow_links: false,
+        })
+        .unwrap();
+
+        // mtime only has second resolution, so make sure it actually changes
+        std::thread::sleep(std::time::Duration::from_secs(1));
+        fs::write(&source_file, "int main() { return 1; }").unwrap();
+
+        update_index(UpdateOpts {
+            index_dir: index_dir.0.clone(),
+            search_dir: search_dir.0.clone(),
+        })
+        .unwrap();
+
+        let name = source_file.to_string_lossy().to_string();
+        assert_eq!(count_docs_for_file_name(&index_dir.0, &name), 1);
+    }
+}
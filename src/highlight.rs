@@ -1,4 +1,4 @@
-use crate::lexer::CTokenizer;
+use crate::lexer::{CodeTokenizer, Language};
 use log::info;
 use tantivy::tokenizer::Tokenizer;
 
@@ -8,12 +8,23 @@ pub struct Highlighter<'a> {
     terms: Vec<&'a str>,
     /// The jump table for a Knuth Morris Pratt search through a document for the phrase.
     leaps: Vec<usize>,
+    /// The maximum edit distance a document token may be from a query term and still count as a
+    /// match. `None` means matches must be byte-exact.
+    fuzzy_distance: Option<u8>,
+    /// The language the query (and the documents it is matched against) are tokenized as.
+    language: Language,
 }
 
 impl<'a> Highlighter<'a> {
-    /// Create a new highlighter
-    pub fn new(terms: &'a str) -> Self {
-        let terms: Vec<_> = CTokenizer
+    /// Create a new highlighter that only reports byte-exact matches.
+    pub fn new(terms: &'a str, language: Language) -> Self {
+        Self::new_fuzzy(terms, language, None)
+    }
+
+    /// Create a new highlighter, optionally tolerating up to `fuzzy_distance` edits (insertions,
+    /// deletions or substitutions) between a query term and the token it matches.
+    pub fn new_fuzzy(terms: &'a str, language: Language, fuzzy_distance: Option<u8>) -> Self {
+        let terms: Vec<_> = CodeTokenizer(language)
             .token_stream(terms)
             .map(|(start, stop)| &terms[start..stop])
             .collect();
@@ -24,7 +35,7 @@ impl<'a> Highlighter<'a> {
         let mut len = 0;
         for term in &terms {
             let new_leap = loop {
-                if term == &terms[len] {
+                if term_matches(fuzzy_distance, term, terms[len]) {
                     len += 1;
                     break len;
                 } else if len == 0 {
@@ -36,13 +47,26 @@ impl<'a> Highlighter<'a> {
             leaps.push(new_leap);
         }
 
-        Highlighter { terms, leaps }
+        Highlighter {
+            terms,
+            leaps,
+            fuzzy_distance,
+            language,
+        }
+    }
+
+    /// Whether `token` should be considered a match for `term`, given this highlighter's fuzzy
+    /// distance.
+    fn matches(&self, token: &str, term: &str) -> bool {
+        term_matches(self.fuzzy_distance, token, term)
     }
 
     /// Use the Knuth Morris Pratt algorithm to search for the search phrase within the input.
     /// Return the (start, stop) indices of each match within the search string.
     pub fn search(&self, input: &str) -> Vec<(usize, usize)> {
-        let tokens = CTokenizer.token_stream(input).collect::<Vec<_>>();
+        let tokens = CodeTokenizer(self.language)
+            .token_stream(input)
+            .collect::<Vec<_>>();
 
         let mut j = 0;
         let mut i = 0;
@@ -53,7 +77,7 @@ impl<'a> Highlighter<'a> {
             let (start, stop) = tokens[i];
             let token = &input[start..stop];
 
-            if token == self.terms[j] {
+            if self.matches(token, self.terms[j]) {
                 j += 1;
                 i += 1;
             }
@@ -65,7 +89,7 @@ impl<'a> Highlighter<'a> {
             } else if i < tokens.len() {
                 let (start, stop) = tokens[i];
                 let token = &input[start..stop];
-                if self.terms[j] != token {
+                if !self.matches(token, self.terms[j]) {
                     if j != 0 {
                         j = self.leaps[j - 1];
                     } else {
@@ -78,3 +102,69 @@ impl<'a> Highlighter<'a> {
         output
     }
 }
+
+/// Whether `token` should be considered a match for `term`, given a fuzzy distance (`None` means
+/// matches must be byte-exact). Shared by `Highlighter::matches` and the KMP failure function
+/// built in `Highlighter::new_fuzzy`, so the jump table stays consistent with the scan that walks
+/// it - otherwise a multi-term fuzzy query could mis-align the two.
+fn term_matches(fuzzy_distance: Option<u8>, token: &str, term: &str) -> bool {
+    match fuzzy_distance {
+        Some(distance) => edit_distance(token, term) <= usize::from(distance),
+        None => token == term,
+    }
+}
+
+/// The Levenshtein (edit) distance between two strings: the minimum number of single-character
+/// insertions, deletions or substitutions needed to turn `a` into `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+
+    let mut row = (0..=b.len()).collect::<Vec<_>>();
+
+    for (i, &a_char) in a.iter().enumerate() {
+        let mut previous_diagonal = row[0];
+        row[0] = i + 1;
+
+        for (j, &b_char) in b.iter().enumerate() {
+            let above = row[j + 1];
+            let cost = if a_char == b_char { 0 } else { 1 };
+
+            let new_value = (above + 1)
+                .min(row[j] + 1)
+                .min(previous_diagonal + cost);
+
+            previous_diagonal = above;
+            row[j + 1] = new_value;
+        }
+    }
+
+    row[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn edit_distance_identical_strings_is_zero() {
+        assert_eq!(edit_distance("hello", "hello"), 0);
+    }
+
+    #[test]
+    fn edit_distance_counts_substitutions() {
+        assert_eq!(edit_distance("kitten", "sitten"), 1);
+    }
+
+    #[test]
+    fn edit_distance_counts_insertions_and_deletions() {
+        assert_eq!(edit_distance("ab", "abc"), 1);
+        assert_eq!(edit_distance("abc", "ab"), 1);
+    }
+
+    #[test]
+    fn edit_distance_against_empty_string_is_the_length() {
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("abc", ""), 3);
+    }
+}
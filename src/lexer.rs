@@ -1,93 +1,230 @@
 use lazy_static::lazy_static;
 use log::warn;
 use regex::{Regex, RegexBuilder, RegexSet, RegexSetBuilder};
-use std::str;
+use std::{collections::HashMap, str};
 use tantivy::tokenizer::{Token, TokenStream, Tokenizer};
 
+/// A programming language that looker knows how to tokenize. Each variant carries its own
+/// ordered set of regular expressions (longest match wins, same as the original C-only lexer)
+/// plus the file extensions it is associated with.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Language {
+    C,
+    Rust,
+    Python,
+    JavaScript,
+}
+
+/// Every language looker supports, in no particular order.
+pub const ALL_LANGUAGES: &[Language] = &[
+    Language::C,
+    Language::Rust,
+    Language::Python,
+    Language::JavaScript,
+];
+
+impl Language {
+    /// The name this language is registered under, both as a tantivy tokenizer name and as the
+    /// suffix of its dedicated content field in the schema.
+    pub fn name(self) -> &'static str {
+        match self {
+            Language::C => "c",
+            Language::Rust => "rust",
+            Language::Python => "python",
+            Language::JavaScript => "javascript",
+        }
+    }
+
+    /// The file extensions (without the leading dot) that belong to this language.
+    pub fn extensions(self) -> &'static [&'static str] {
+        match self {
+            Language::C => &["c", "h"],
+            Language::Rust => &["rs"],
+            Language::Python => &["py"],
+            Language::JavaScript => &["js"],
+        }
+    }
+
+    /// Work out which language, if any, a file extension belongs to.
+    pub fn from_extension(extension: &str) -> Option<Language> {
+        ALL_LANGUAGES
+            .iter()
+            .copied()
+            .find(|language| language.extensions().contains(&extension))
+    }
+
+    /// Work out which language, if any, is registered under the given name.
+    pub fn from_name(name: &str) -> Option<Language> {
+        ALL_LANGUAGES.iter().copied().find(|language| language.name() == name)
+    }
+
+    /// The ordered, longest-match-wins regular expressions used to tokenize this language.
+    fn regular_expressions(self) -> &'static [&'static str] {
+        match self {
+            Language::C => C_REGULAR_EXPRESSIONS,
+            Language::Rust => RUST_REGULAR_EXPRESSIONS,
+            Language::Python => PYTHON_REGULAR_EXPRESSIONS,
+            Language::JavaScript => JAVASCRIPT_REGULAR_EXPRESSIONS,
+        }
+    }
+}
+
+static C_REGULAR_EXPRESSIONS: &[&str] = &[
+    // comments
+    r"^/\*([^*]|\*[^/])*\*/",
+    r"^//([^\n\\]*\\\n)*[^\n]*\n",
+    // quotes
+    r#"^"([^"]|\\")*""#,
+    r"^'(\\?[^'\n]|\\')'",
+    // preprocessor
+    r"^#(\S*)",
+    r"^<[^>]+>", // for #include
+    // parens
+    r"^[()\[\]{}]",
+    // operators
+    r"^(->|<<|>>|\|\||&&|--|\+\+|[-+*|&%/=]=)",
+    r"^[-<>~!%^&*/+=?|.,:;]",
+    // identifier
+    r"^[_A-Za-z]\w*",
+    // constants
+    r"^[0-9]*\.?[0-9]+([eE][-+]?[0-9]+)?",
+    // whitespace
+    r"^\s+",
+];
+
+static RUST_REGULAR_EXPRESSIONS: &[&str] = &[
+    // comments
+    r"^/\*([^*]|\*[^/])*\*/",
+    r"^//([^\n]*)\n",
+    // quotes
+    r#"^"([^"]|\\")*""#,
+    r"^'(\\?[^'\n]|\\')'",
+    // attributes and macro invocations
+    r"^#!?\[[^\]]*\]",
+    r"^[_A-Za-z]\w*!",
+    // lifetimes
+    r"^'[_A-Za-z]\w*",
+    // parens
+    r"^[()\[\]{}]",
+    // operators
+    r"^(->|=>|::|<<|>>|\|\||&&|\.\.=|\.\.\.|\.\.|[-+*|&%/=<>!]=)",
+    r"^[-<>~!%^&*/+=?|.,:;#]",
+    // identifier
+    r"^[_A-Za-z]\w*",
+    // constants
+    r"^[0-9]*\.?[0-9]+([eE][-+]?[0-9]+)?",
+    // whitespace
+    r"^\s+",
+];
+
+static PYTHON_REGULAR_EXPRESSIONS: &[&str] = &[
+    // comments
+    r"^#[^\n]*\n",
+    // triple-quoted strings
+    r#"^"""([^\\]|\\.)*?""""#,
+    r"^'''([^\\]|\\.)*?'''",
+    // quotes
+    r#"^"([^"]|\\")*""#,
+    r"^'([^']|\\')*'",
+    // parens
+    r"^[()\[\]{}]",
+    // operators
+    r"^(\*\*=?|//=?|<<=?|>>=?|==|!=|<=|>=|->|:=|[-+*/%&|^=<>]=)",
+    r"^[-<>~!%^&*/+=?|.,:;@]",
+    // identifier
+    r"^[_A-Za-z]\w*",
+    // constants
+    r"^[0-9]*\.?[0-9]+([eE][-+]?[0-9]+)?",
+    // whitespace
+    r"^\s+",
+];
+
+static JAVASCRIPT_REGULAR_EXPRESSIONS: &[&str] = &[
+    // comments
+    r"^/\*([^*]|\*[^/])*\*/",
+    r"^//([^\n]*)\n",
+    // quotes
+    r#"^"([^"]|\\")*""#,
+    r"^'([^']|\\')*'",
+    r"^`([^`]|\\`)*`",
+    // parens
+    r"^[()\[\]{}]",
+    // operators
+    r"^(===|!==|\*\*=?|=>|\?\?|\.\.\.|<<=?|>>>?=?|==|!=|<=|>=|&&|\|\||[-+*/%&|^=<>]=)",
+    r"^[-<>~!%^&*/+=?|.,:;]",
+    // identifier
+    r"^[_$A-Za-z][\w$]*",
+    // constants
+    r"^[0-9]*\.?[0-9]+([eE][-+]?[0-9]+)?",
+    // whitespace
+    r"^\s+",
+];
+
+/// A language's regular expressions, compiled once and cached for the lifetime of the process.
+struct CompiledLanguage {
+    regex_set: RegexSet,
+    regexes: Vec<Regex>,
+}
+
 lazy_static! {
-    static ref REGULAR_EXPRESSIONS: &'static [&'static str] = &[
-        // comments
-        r"^/\*([^*]|\*[^/])*\*/",
-        r"^//([^\n\\]*\\\n)*[^\n]*\n",
-        // quotes
-        r#"^"([^"]|\\")*""#,
-        r"^'(\\?[^'\n]|\\')'",
-        // preprocessor
-        r"^#(\S*)",
-        r"^<[^>]+>", // for #include
-        // parens
-        r"^[()\[\]{}]",
-        // operators
-        r"^(->|<<|>>|\|\||&&|--|\+\+|[-+*|&%/=]=)",
-        r"^[-<>~!%^&*/+=?|.,:;]",
-        // identifier
-        r"^[_A-Za-z]\w*",
-        // constants
-        r"^[0-9]*\.?[0-9]+([eE][-+]?[0-9]+)?",
-        // whitespace
-        r"^\s+",
-    ];
-    static ref COMPILED_REGULAR_EXPRESSIONS: Vec<Regex> = REGULAR_EXPRESSIONS
+    static ref COMPILED: HashMap<Language, CompiledLanguage> = ALL_LANGUAGES
         .iter()
-        .map(|regex| {
-            RegexBuilder::new(regex)
+        .map(|&language| {
+            let patterns = language.regular_expressions();
+            let regex_set = RegexSetBuilder::new(patterns)
                 .dot_matches_new_line(true)
                 .build()
-                .unwrap()
-        })
-        .collect();
-    static ref COMPILED_RECOVERY_REGULAR_EXPRESSIONS: Vec<Regex> = REGULAR_EXPRESSIONS
-        .iter()
-        .map(|regex| {
-            RegexBuilder::new(regex)
-                .dot_matches_new_line(true)
-                .multi_line(true)
-                .build()
-                .unwrap()
+                .unwrap();
+            let regexes = patterns
+                .iter()
+                .map(|pattern| {
+                    RegexBuilder::new(pattern)
+                        .dot_matches_new_line(true)
+                        .build()
+                        .unwrap()
+                })
+                .collect();
+
+            (language, CompiledLanguage { regex_set, regexes })
         })
         .collect();
-    static ref REGEX_SET: RegexSet = RegexSetBuilder::new(&REGULAR_EXPRESSIONS[..])
-        .dot_matches_new_line(true)
-        .build()
-        .unwrap();
-    static ref REGEX_SET_RECOVERY: RegexSet = RegexSetBuilder::new(&REGULAR_EXPRESSIONS[..])
-        .dot_matches_new_line(true)
-        .multi_line(true)
-        .build()
-        .unwrap();
 }
 
-/// A tokenizer for the C programming language, powered by sublime text syntax highlighting file.
+/// A tokenizer for a single programming language, powered by sublime text syntax highlighting
+/// file conventions.
 #[derive(Debug, Copy, Clone)]
-pub struct CTokenizer;
+pub struct CodeTokenizer(pub Language);
 
-impl<'a> Tokenizer<'a> for CTokenizer {
-    type TokenStreamImpl = CTokenStream<'a>;
+impl<'a> Tokenizer<'a> for CodeTokenizer {
+    type TokenStreamImpl = CodeTokenStream<'a>;
 
     fn token_stream(&self, text: &'a str) -> Self::TokenStreamImpl {
         let token = Token::default();
         let elapsed = 0;
 
-        CTokenStream {
+        CodeTokenStream {
             text,
             token,
             elapsed,
+            language: self.0,
         }
     }
 }
 
-/// A stream of C programming language tokens
+/// A stream of tokens for a single programming language.
 #[derive(Debug)]
-pub struct CTokenStream<'a> {
+pub struct CodeTokenStream<'a> {
     /// The start of the file itself, for token referencing
     text: &'a str,
     /// The token currently being investigated
     token: Token,
     /// The amount of characters currently consumed
     elapsed: usize,
+    /// Which language's regular expressions to lex with
+    language: Language,
 }
 
-impl<'a> Iterator for CTokenStream<'a> {
+impl<'a> Iterator for CodeTokenStream<'a> {
     // start, stop
     type Item = (usize, usize);
 
@@ -97,8 +234,10 @@ impl<'a> Iterator for CTokenStream<'a> {
     }
 }
 
-impl<'a> TokenStream for CTokenStream<'a> {
+impl<'a> TokenStream for CodeTokenStream<'a> {
     fn advance(&mut self) -> bool {
+        let compiled = &COMPILED[&self.language];
+
         loop {
             let &mut Self {
                 text,
@@ -108,10 +247,8 @@ impl<'a> TokenStream for CTokenStream<'a> {
 
             let position = {
                 // try to get the next token on this line
-                match &REGEX_SET.matches(text).iter().collect::<Vec<_>>()[..] {
-                    [single_regex] => COMPILED_REGULAR_EXPRESSIONS[*single_regex]
-                        .find(text)
-                        .unwrap(),
+                match &compiled.regex_set.matches(text).iter().collect::<Vec<_>>()[..] {
+                    [single_regex] => compiled.regexes[*single_regex].find(text).unwrap(),
                     [] => {
                         if text != "" {
                             warn!("Aborting lex");
@@ -120,7 +257,7 @@ impl<'a> TokenStream for CTokenStream<'a> {
                     }
                     multiple_matches => multiple_matches
                         .into_iter()
-                        .map(|&index| COMPILED_REGULAR_EXPRESSIONS[index].find(text).unwrap())
+                        .map(|&index| compiled.regexes[index].find(text).unwrap())
                         .max_by_key(|position| position.end() - position.start())
                         .unwrap(),
                 }
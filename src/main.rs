@@ -5,10 +5,12 @@ mod build;
 mod highlight;
 mod lexer;
 mod search;
+mod serve;
 
 use self::{
-    build::{build_index, BuildOpts},
+    build::{build_index, update_index, BuildOpts, UpdateOpts},
     search::{search_index, SearchOpts},
+    serve::{serve_index, ServeOpts},
 };
 
 #[derive(Debug, StructOpt)]
@@ -17,9 +19,15 @@ enum Options {
     /// Build an index for later searching.
     #[structopt(name = "build")]
     Build(BuildOpts),
+    /// Incrementally re-index a repository, only touching files that have changed.
+    #[structopt(name = "update")]
+    Update(UpdateOpts),
     /// Search the existing index (will fail if the index does not exist).
     #[structopt(name = "search")]
     Search(SearchOpts),
+    /// Serve search results over HTTP as JSON.
+    #[structopt(name = "serve")]
+    Serve(ServeOpts),
 }
 
 fn main() -> Result<(), Error> {
@@ -28,6 +36,8 @@ fn main() -> Result<(), Error> {
 
     match opts {
         Options::Build(opts) => build_index(opts),
+        Options::Update(opts) => update_index(opts),
         Options::Search(opts) => search_index(opts),
+        Options::Serve(opts) => serve_index(opts),
     }
 }
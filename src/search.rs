@@ -1,11 +1,20 @@
-use crate::{highlight::Highlighter, lexer::CTokenizer};
+use crate::{
+    build::{content_field_name, register_tokenizers},
+    highlight::Highlighter,
+    lexer::{CodeTokenizer, Language},
+};
 use failure::{bail, format_err, Error};
 use log::debug;
-use std::{collections::BTreeMap, iter, path::PathBuf};
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    iter,
+    path::PathBuf,
+};
 use structopt::StructOpt;
 use tantivy::{
     collector::TopDocs,
-    query::{PhraseQuery, Query, TermQuery},
+    query::{BooleanQuery, FuzzyTermQuery, Occur, PhraseQuery, Query, TermQuery},
     schema::{IndexRecordOption, Value},
     tokenizer::{TokenStream, Tokenizer},
     Index, Term,
@@ -21,81 +30,258 @@ pub struct SearchOpts {
     /// The maximum number of search results to return
     #[structopt(long = "limit", short = "l", default_value = "3")]
     limit: usize,
+    /// Tolerate typos in the query, matching terms within N edits (0-2, defaults to 2 if no value
+    /// is given). Values above 2 are clamped, since the underlying Levenshtein automaton only
+    /// meaningfully supports distances up to 2.
+    #[structopt(long = "fuzzy", min_values = 0, max_values = 1)]
+    fuzzy: Option<Option<u8>>,
+    /// Which language's tokenizer to use when parsing the query and matching documents.
+    #[structopt(long = "language", default_value = "c")]
+    language: String,
+    /// Print N lines of context before and after each match, like ripgrep's `-C`.
+    #[structopt(long = "context", short = "C", default_value = "0")]
+    context: usize,
+    /// Print N lines of context before each match. Overrides `--context` for the leading edge.
+    #[structopt(long = "before")]
+    before: Option<usize>,
+    /// Print N lines of context after each match. Overrides `--context` for the trailing edge.
+    #[structopt(long = "after")]
+    after: Option<usize>,
     /// The phrase to search for.
     query: String,
 }
 
-pub fn search_index(opts: SearchOpts) -> Result<(), Error> {
-    let SearchOpts {
-        index_dir,
-        query: query_string,
-        limit,
-    } = opts;
-    let index = Index::open_in_dir(index_dir)?;
-    index.tokenizers().register("c", CTokenizer);
+/// A single search hit. Serializes as JSON for the `serve` command's `/search` endpoint; the
+/// `file_contents` field is kept around for the CLI to print source lines from, but is not part
+/// of that representation.
+#[derive(Debug, Serialize)]
+pub struct SearchHit {
+    pub file_name: String,
+    pub score: f32,
+    /// The (start, stop) byte ranges of every match within `file_contents`.
+    pub matches: Vec<(usize, usize)>,
+    #[serde(skip)]
+    pub file_contents: String,
+}
+
+/// The largest edit distance the Levenshtein automaton behind fuzzy matching meaningfully
+/// supports; anything a caller asks for beyond this is clamped down to it.
+const MAX_FUZZY_DISTANCE: u8 = 2;
 
+/// Run a query against an already-open index, returning structured hits rather than printing
+/// anything. Shared by the CLI `search` command and the `serve` command's `/search` endpoint.
+pub fn run_search(
+    index: &Index,
+    query_string: &str,
+    limit: usize,
+    language: Language,
+    fuzzy_distance: Option<u8>,
+) -> Result<Vec<SearchHit>, Error> {
+    let fuzzy_distance = fuzzy_distance.map(|distance| distance.min(MAX_FUZZY_DISTANCE));
     let schema = index.schema();
-    let file_name = schema
+    let file_name_field = schema
         .get_field("file_name")
         .ok_or_else(|| format_err!("Cannot find field 'file_name' in index"))?;
-    let file_contents = schema
-        .get_field("file_contents")
-        .ok_or_else(|| format_err!("Cannot find field 'file_contents' in index"))?;
+    let file_contents_field = schema
+        .get_field(&content_field_name(language))
+        .ok_or_else(|| format_err!("Cannot find field '{:?}' in index", language))?;
 
-    let tokens = CTokenizer.token_stream(query_string.as_str());
+    let tokens = CodeTokenizer(language).token_stream(query_string);
 
     let mut terms = tokens
         // convert to strings
         .map(|(start, stop)| &query_string[start..stop])
         .inspect(|text| debug!("Token {:?}", text))
         // convert to terms
-        .map(|text| Term::from_field_text(file_contents, text))
+        .map(|text| Term::from_field_text(file_contents_field, text))
         .collect::<Vec<_>>();
 
-    let query = if terms.len() == 1 {
-        let term = terms.remove(0);
-        Box::new(TermQuery::new(
-            term,
-            IndexRecordOption::WithFreqsAndPositions,
-        )) as Box<dyn Query>
-    } else {
-        Box::new(PhraseQuery::new(terms)) as Box<dyn Query>
+    let query = match (fuzzy_distance, terms.len()) {
+        (Some(distance), 1) => {
+            let term = terms.remove(0);
+            Box::new(FuzzyTermQuery::new(term, distance, true)) as Box<dyn Query>
+        }
+        (Some(distance), _) => {
+            let clauses = terms
+                .into_iter()
+                .map(|term| {
+                    let fuzzy = Box::new(FuzzyTermQuery::new(term, distance, true)) as Box<dyn Query>;
+                    (Occur::Should, fuzzy)
+                })
+                .collect::<Vec<_>>();
+            Box::new(BooleanQuery::from(clauses)) as Box<dyn Query>
+        }
+        (None, 1) => {
+            let term = terms.remove(0);
+            Box::new(TermQuery::new(
+                term,
+                IndexRecordOption::WithFreqsAndPositions,
+            )) as Box<dyn Query>
+        }
+        (None, _) => Box::new(PhraseQuery::new(terms)) as Box<dyn Query>,
     };
 
     let searcher = index.reader()?.searcher();
-    let results: Vec<_> = searcher.search(&query, &TopDocs::with_limit(limit))?;
-
-    let highlighter = Highlighter::new(&query_string);
-    for (_score, result) in results {
-        let doc = searcher.doc(result)?;
-        let file_name = {
-            let contents = doc
-                .get_first(file_name)
-                .ok_or(format_err!("No file name"))?;
-            match contents {
-                Value::Str(text) => text,
-                val => bail!("Invalid value for 'file_name' {:?}", val),
-            }
-        };
-        let file_contents = {
-            let contents = doc
-                .get_first(file_contents)
-                .ok_or(format_err!("No file contents"))?;
-            match contents {
-                Value::Str(text) => text,
-                val => bail!("Invalid value for 'file_contents' {:?}", val),
-            }
-        };
+    // `TopDocs::with_limit` panics on 0, so clamp here rather than trusting every caller
+    // (the CLI and the `serve` endpoint both pass user-supplied limits through this function).
+    let results: Vec<_> = searcher.search(&query, &TopDocs::with_limit(limit.max(1)))?;
+
+    let highlighter = Highlighter::new_fuzzy(query_string, language, fuzzy_distance);
+    let hits = results
+        .into_iter()
+        .map(|(score, address)| {
+            let doc = searcher.doc(address)?;
+            let file_name = match doc.get_first(file_name_field) {
+                Some(Value::Str(text)) => text.clone(),
+                Some(val) => bail!("Invalid value for 'file_name' {:?}", val),
+                None => bail!("No file name"),
+            };
+            let file_contents = match doc.get_first(file_contents_field) {
+                Some(Value::Str(text)) => text.clone(),
+                Some(val) => bail!("Invalid value for 'file_contents' {:?}", val),
+                None => bail!("No file contents"),
+            };
+            let matches = highlighter.search(&file_contents);
+
+            Ok(SearchHit {
+                file_name,
+                score,
+                matches,
+                file_contents,
+            })
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
 
-        println!("{}{}{}", Fg(Blue), file_name, Fg(Reset));
-        print_lines(&highlighter, file_contents);
+    Ok(hits)
+}
+
+pub fn search_index(opts: SearchOpts) -> Result<(), Error> {
+    let SearchOpts {
+        index_dir,
+        query: query_string,
+        limit,
+        fuzzy,
+        language,
+        context,
+        before,
+        after,
+    } = opts;
+    let before_context = before.unwrap_or(context);
+    let after_context = after.unwrap_or(context);
+    let fuzzy_distance = fuzzy.map(|distance| distance.unwrap_or(2));
+    let language = Language::from_name(&language)
+        .ok_or_else(|| format_err!("Unknown language '{}'", language))?;
+
+    let index = Index::open_in_dir(index_dir)?;
+    register_tokenizers(&index);
+
+    let hits = run_search(&index, &query_string, limit, language, fuzzy_distance)?;
+
+    for hit in hits {
+        println!("{}{}{}", Fg(Blue), hit.file_name, Fg(Reset));
+        print_lines(&hit.file_contents, &hit.matches, before_context, after_context);
     }
 
     Ok(())
 }
 
-fn print_lines(highlighter: &Highlighter, contents: &str) {
-    let matches = highlighter.search(contents);
+/// One line of a file, along with its 1-indexed line number and its byte range within the
+/// file's contents.
+struct Line<'a> {
+    number: usize,
+    start: usize,
+    stop: usize,
+    text: &'a str,
+}
+
+fn print_lines(contents: &str, matches: &[(usize, usize)], before_context: usize, after_context: usize) {
+    if matches.is_empty() {
+        return;
+    }
+
+    let lines: Vec<_> = contents
+        .lines()
+        .enumerate()
+        .map(|(index, text)| {
+            let start = (text.as_ptr() as usize) - (contents.as_ptr() as usize);
+            let stop = start + text.len();
+            Line {
+                number: index + 1,
+                start,
+                stop,
+                text,
+            }
+        })
+        .collect();
+
+    // every line index that a match overlaps with, even partially
+    let matched_line_indices: BTreeSet<usize> = lines
+        .iter()
+        .enumerate()
+        .filter(|(_index, line)| {
+            matches
+                .iter()
+                .any(|(match_start, match_stop)| *match_start < line.stop && *match_stop > line.start)
+        })
+        .map(|(index, _line)| index)
+        .collect();
+
+    let hunks = merge_hunks(&matched_line_indices, before_context, after_context, lines.len());
+
+    for (hunk_index, &(lo, hi)) in hunks.iter().enumerate() {
+        if hunk_index > 0 {
+            println!("--");
+        }
+
+        let mut currently_inside_pattern = false;
+        for line in &lines[lo..=hi] {
+            print_line(line, matches, matched_line_indices.contains(&(line.number - 1)), &mut currently_inside_pattern);
+        }
+    }
+}
+
+/// Expand each matched line index into a (lo, hi) hunk covering its context, merging
+/// adjacent/overlapping hunks the way ripgrep does. `num_lines` clamps context expansion to the
+/// bounds of the file.
+fn merge_hunks(
+    matched_line_indices: &BTreeSet<usize>,
+    before_context: usize,
+    after_context: usize,
+    num_lines: usize,
+) -> Vec<(usize, usize)> {
+    let mut hunks: Vec<(usize, usize)> = Vec::new();
+    for &index in matched_line_indices {
+        let lo = index.saturating_sub(before_context);
+        let hi = (index + after_context).min(num_lines - 1);
+
+        match hunks.last_mut() {
+            Some(last) if lo <= last.1 + 1 => last.1 = last.1.max(hi),
+            _ => hunks.push((lo, hi)),
+        }
+    }
+    hunks
+}
+
+/// Print a single line, gutter-prefixed with its line number the way ripgrep does (`:` for a
+/// matching line, `-` for a line only shown for context), highlighting any matched regions.
+fn print_line(line: &Line, matches: &[(usize, usize)], is_match: bool, currently_inside_pattern: &mut bool) {
+    let separator = if is_match { ':' } else { '-' };
+    print!("{}{}", line.number, separator);
+
+    if !is_match {
+        // a match can end partway through the gap between two lines (e.g. inside a line
+        // terminator swallowed by a multi-line token) without actually overlapping this line, so
+        // the colour left on from the previous line still needs closing out here.
+        if *currently_inside_pattern {
+            print!("{}", Fg(Reset));
+            *currently_inside_pattern = false;
+        }
+        println!("{}", line.text);
+        return;
+    }
+
+    let Line { start, stop, text, .. } = *line;
+
     let points_of_interest = matches
         .iter()
         .enumerate()
@@ -104,68 +290,87 @@ fn print_lines(highlighter: &Highlighter, contents: &str) {
         })
         .collect::<BTreeMap<_, _>>();
 
-    let mut currently_inside_pattern = false;
-
-    'line: for line in contents.lines() {
-        let mut printed_anything_this_line = false;
-
-        let start = (line.as_ptr() as usize) - (contents.as_ptr() as usize);
-        let stop = start + line.len();
-
-        let mut relevant_matches = points_of_interest
-            // get the relevant matches that start or stop inside this line
-            .range(start..stop)
-            .map(|(_position, this_match)| this_match)
-            // turn index into (start, stop)
-            .map(|index| matches[*index])
-            // get ready for iterating through
-            .sorted()
-            .dedup();
-
-        let mut last_seen = if currently_inside_pattern {
-            // find the end of the pattern at the start of this line
-            match relevant_matches.next() {
-                Some((_start, match_stop)) if match_stop <= stop => {
-                    let match_stop = match_stop - start;
-
-                    printed_anything_this_line = true;
-                    print!("{}{}", &line[..match_stop], Fg(Reset));
-
-                    match_stop
-                }
-                _ => {
-                    // we are currently inside a pattern
-                    // no patterns start or stop on this line
-                    // therefore this entire line is just part of the pattern
-                    println!("{}", line);
-                    continue 'line;
-                }
-            }
-        } else {
-            // start from the beginning of this line, as we are not currently inside a pattern
-            0
-        };
-        currently_inside_pattern = false;
-
-        for (match_start, match_stop) in relevant_matches {
-            let match_start = match_start - start;
-
-            printed_anything_this_line = true;
-            print!("{}", &line[last_seen..match_start]);
-
-            if match_stop > stop {
-                currently_inside_pattern = true;
-                println!("{}{}", Fg(Red), &line[match_start..]);
-                continue 'line;
-            } else {
+    let mut relevant_matches = points_of_interest
+        // get the relevant matches that start or stop inside this line
+        .range(start..stop)
+        .map(|(_position, this_match)| this_match)
+        // turn index into (start, stop)
+        .map(|index| matches[*index])
+        // get ready for iterating through
+        .sorted()
+        .dedup();
+
+    let mut last_seen = if *currently_inside_pattern {
+        // find the end of the pattern at the start of this line
+        match relevant_matches.next() {
+            Some((_start, match_stop)) if match_stop <= stop => {
                 let match_stop = match_stop - start;
-                print!("{}{}{}", Fg(Red), &line[match_start..match_stop], Fg(Reset));
-                last_seen = match_stop;
+                print!("{}{}", &text[..match_stop], Fg(Reset));
+                match_stop
+            }
+            _ => {
+                // we are currently inside a pattern, and no patterns start or stop on this line,
+                // so this entire line is just part of the pattern
+                println!("{}", text);
+                return;
             }
         }
+    } else {
+        // start from the beginning of this line, as we are not currently inside a pattern
+        0
+    };
+    *currently_inside_pattern = false;
+
+    for (match_start, match_stop) in relevant_matches {
+        let match_start = match_start - start;
 
-        if printed_anything_this_line {
-            println!("{}", &line[last_seen..]);
+        print!("{}", &text[last_seen..match_start]);
+
+        if match_stop > stop {
+            *currently_inside_pattern = true;
+            println!("{}{}", Fg(Red), &text[match_start..]);
+            return;
+        } else {
+            let match_stop = match_stop - start;
+            print!("{}{}{}", Fg(Red), &text[match_start..match_stop], Fg(Reset));
+            last_seen = match_stop;
         }
     }
+
+    println!("{}", &text[last_seen..]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn indices(values: &[usize]) -> BTreeSet<usize> {
+        values.iter().copied().collect()
+    }
+
+    #[test]
+    fn merge_hunks_keeps_distant_matches_separate() {
+        let hunks = merge_hunks(&indices(&[2, 10]), 0, 0, 20);
+        assert_eq!(hunks, vec![(2, 2), (10, 10)]);
+    }
+
+    #[test]
+    fn merge_hunks_merges_overlapping_context() {
+        let hunks = merge_hunks(&indices(&[2, 5]), 2, 2, 20);
+        assert_eq!(hunks, vec![(0, 7)]);
+    }
+
+    #[test]
+    fn merge_hunks_merges_adjacent_hunks() {
+        // context for the first match ends on the line just before the second match's context
+        // begins
+        let hunks = merge_hunks(&indices(&[2, 5]), 1, 1, 20);
+        assert_eq!(hunks, vec![(1, 6)]);
+    }
+
+    #[test]
+    fn merge_hunks_clamps_to_file_bounds() {
+        let hunks = merge_hunks(&indices(&[0, 9]), 3, 3, 10);
+        assert_eq!(hunks, vec![(0, 3), (6, 9)]);
+    }
 }
@@ -0,0 +1,187 @@
+use crate::{
+    build::register_tokenizers,
+    lexer::Language,
+    search::run_search,
+};
+use failure::{format_err, Error};
+use log::{info, warn};
+use std::{collections::HashMap, path::PathBuf};
+use structopt::StructOpt;
+use tantivy::Index;
+use tiny_http::{Header, Request, Response, Server};
+
+/// The serve command opens an existing index and answers search queries over HTTP, as JSON.
+#[derive(Debug, StructOpt)]
+pub struct ServeOpts {
+    /// The directory that the index is located in.
+    #[structopt(long = "index-dir", parse(from_os_str), default_value = ".looker")]
+    index_dir: PathBuf,
+    /// The address to listen for HTTP requests on.
+    #[structopt(long = "address", default_value = "127.0.0.1:8080")]
+    address: String,
+}
+
+/// Open an existing index and run an HTTP server exposing it, blocking forever.
+pub fn serve_index(opts: ServeOpts) -> Result<(), Error> {
+    let ServeOpts {
+        index_dir,
+        address,
+    } = opts;
+
+    let index = Index::open_in_dir(index_dir)?;
+    register_tokenizers(&index);
+
+    let server = Server::http(&address)
+        .map_err(|error| format_err!("Binding HTTP server on {}: {}", address, error))?;
+    info!("Listening on http://{}", address);
+
+    for request in server.incoming_requests() {
+        let method = request.method().clone();
+        let url = request.url().to_string();
+
+        if let Err(error) = handle_request(&index, request) {
+            warn!("Handling {} {}: {:?}", method, url, error);
+        }
+    }
+
+    Ok(())
+}
+
+fn handle_request(index: &Index, request: Request) -> Result<(), Error> {
+    let (path, query) = split_url(request.url());
+
+    let body = match path {
+        "/search" => match handle_search(index, &query) {
+            Ok(body) => Response::from_string(body).with_status_code(200),
+            Err(error) => Response::from_string(format!(r#"{{"error":{:?}}}"#, error.to_string()))
+                .with_status_code(400),
+        },
+        _ => Response::from_string(r#"{"error":"not found"}"#).with_status_code(404),
+    };
+
+    request
+        .respond(body.with_header(json_content_type()))
+        .map_err(Error::from)
+}
+
+/// The largest `limit` a client may request, regardless of what they ask for, so a single HTTP
+/// request can't force an unbounded collector over the whole index.
+const MAX_LIMIT: usize = 100;
+
+/// Run a `/search?q=...&limit=...&language=...&fuzzy=...` query, returning the response body.
+fn handle_search(index: &Index, query: &HashMap<String, String>) -> Result<String, Error> {
+    let query_string = query
+        .get("q")
+        .ok_or_else(|| format_err!("Missing required query parameter 'q'"))?;
+    let limit = query
+        .get("limit")
+        .map(|limit| limit.parse())
+        .transpose()?
+        .unwrap_or(3)
+        .max(1)
+        .min(MAX_LIMIT);
+    let language = query
+        .get("language")
+        .map(String::as_str)
+        .unwrap_or("c");
+    let language = Language::from_name(language)
+        .ok_or_else(|| format_err!("Unknown language '{}'", language))?;
+    let fuzzy_distance = query
+        .get("fuzzy")
+        .map(|distance| distance.parse())
+        .transpose()?;
+
+    let hits = run_search(index, query_string, limit, language, fuzzy_distance)?;
+
+    Ok(serde_json::to_string(&hits)?)
+}
+
+fn json_content_type() -> Header {
+    Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..]).unwrap()
+}
+
+/// Split a request URL like `/search?q=foo&limit=3` into its path and decoded query parameters.
+fn split_url(url: &str) -> (&str, HashMap<String, String>) {
+    match url.find('?') {
+        Some(index) => (&url[..index], parse_query_string(&url[index + 1..])),
+        None => (url, HashMap::new()),
+    }
+}
+
+fn parse_query_string(query: &str) -> HashMap<String, String> {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let mut parts = pair.splitn(2, '=');
+            let key = parts.next().unwrap_or("");
+            let value = parts.next().unwrap_or("");
+            (percent_decode(key), percent_decode(value))
+        })
+        .collect()
+}
+
+/// A minimal `application/x-www-form-urlencoded` decoder: turns `+` into spaces and `%XX` into
+/// the byte it encodes. Bytes are accumulated and decoded as UTF-8 together, since a single
+/// encoded character (e.g. an accented letter) is often spread across several `%XX` sequences.
+fn percent_decode(input: &str) -> String {
+    let mut bytes = Vec::with_capacity(input.len());
+    let mut chars = input.chars();
+
+    while let Some(character) = chars.next() {
+        match character {
+            '+' => bytes.push(b' '),
+            '%' => {
+                let hex: String = chars.by_ref().take(2).collect();
+                match u8::from_str_radix(&hex, 16) {
+                    Ok(byte) => bytes.push(byte),
+                    Err(_) => {
+                        bytes.push(b'%');
+                        bytes.extend_from_slice(hex.as_bytes());
+                    }
+                }
+            }
+            other => {
+                let mut buffer = [0u8; 4];
+                bytes.extend_from_slice(other.encode_utf8(&mut buffer).as_bytes());
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percent_decode_handles_plus_and_hex_escapes() {
+        assert_eq!(percent_decode("hello+world%21"), "hello world!");
+    }
+
+    #[test]
+    fn percent_decode_joins_multi_byte_utf8_escapes() {
+        // "café" with the 'é' percent-encoded as its two UTF-8 bytes
+        assert_eq!(percent_decode("caf%C3%A9"), "café");
+    }
+
+    #[test]
+    fn percent_decode_leaves_invalid_escapes_untouched() {
+        assert_eq!(percent_decode("100%"), "100%");
+        assert_eq!(percent_decode("100%zz"), "100%zz");
+    }
+
+    #[test]
+    fn parse_query_string_decodes_keys_and_values() {
+        let parsed = parse_query_string("q=foo+bar&limit=3");
+        assert_eq!(parsed.get("q").map(String::as_str), Some("foo bar"));
+        assert_eq!(parsed.get("limit").map(String::as_str), Some("3"));
+    }
+
+    #[test]
+    fn parse_query_string_ignores_empty_pairs() {
+        let parsed = parse_query_string("q=foo&&limit=3&");
+        assert_eq!(parsed.len(), 2);
+    }
+}